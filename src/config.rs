@@ -0,0 +1,103 @@
+use serde::{Serialize, Deserialize};
+use miette::{Context, IntoDiagnostic, Result, miette};
+use std::{io::ErrorKind, path::PathBuf};
+
+const DEFAULT_INSTALL_DIR: &str = "/opt/neovim";
+const DEFAULT_BINARY_NAME: &str = "nvim.appimage";
+const DEFAULT_API_BASE: &str = "https://api.github.com/repos/neovim/neovim/releases";
+/// The checksum asset's filename as published upstream. Fixed regardless of
+/// `binary_name`, which only renames the *local* copy of the AppImage.
+const UPSTREAM_SUM_NAME: &str = "nvim.appimage.sha256sum";
+
+/// User-configurable install locations and endpoints.
+///
+/// Loaded from `$XDG_CONFIG_HOME/nvim_upgrade/config.toml`; any missing field
+/// falls back to the historical `/opt/neovim` defaults.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub install_dir: PathBuf,
+    pub binary_name: String,
+    pub api_base: String
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            install_dir: PathBuf::from(DEFAULT_INSTALL_DIR),
+            binary_name: DEFAULT_BINARY_NAME.to_owned(),
+            api_base: DEFAULT_API_BASE.to_owned()
+        }
+    }
+}
+
+/// Concrete paths and endpoints resolved from a [`Config`] at startup.
+pub struct Paths {
+    pub version: PathBuf,
+    pub version_bak: PathBuf,
+    pub app_path: PathBuf,
+    pub app_new: PathBuf,
+    pub app_bak: PathBuf,
+    pub sum_path: PathBuf,
+    pub sum_name: String,
+    pub api_base: String
+}
+
+impl Config {
+    /// Expand the config into the set of files the tool reads and writes.
+    pub fn paths(&self) -> Paths {
+        let sibling = |ext: &str| self.install_dir.join(format!("{}{ext}", self.binary_name));
+        Paths {
+            version: self.install_dir.join("current_version"),
+            version_bak: self.install_dir.join("current_version.bak"),
+            app_path: sibling(""),
+            app_new: sibling(".new"),
+            app_bak: sibling(".bak"),
+            sum_path: sibling(".sha256sum"),
+            sum_name: UPSTREAM_SUM_NAME.to_owned(),
+            api_base: self.api_base.clone()
+        }
+    }
+}
+
+/// The location of the config file under `$XDG_CONFIG_HOME` (or `~/.config`).
+pub fn config_path() -> Result<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .ok_or_else(|| miette!("Neither XDG_CONFIG_HOME nor HOME is set"))?;
+    Ok(base.join("nvim_upgrade").join("config.toml"))
+}
+
+/// Load the config, falling back to defaults when the file does not exist.
+pub fn load() -> Result<Config> {
+    let path = config_path()?;
+    match std::fs::read_to_string(&path) {
+        Ok(raw) => toml::from_str(&raw).into_diagnostic()
+            .with_context(|| format!("Failed to parse '{}'", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e).into_diagnostic().with_context(|| format!("Failed to read '{}'", path.display()))
+    }
+}
+
+/// Create the install directory and write a default config if none exists.
+///
+/// Leaves an existing `config.toml` untouched rather than overwriting a
+/// user's customized `install_dir`/`binary_name`/`api_base`.
+pub fn init() -> Result<()> {
+    let config = Config::default();
+    std::fs::create_dir_all(&config.install_dir).into_diagnostic()
+        .with_context(|| format!("Failed to create '{}'", config.install_dir.display()))?;
+
+    let path = config_path()?;
+    if path.try_exists().into_diagnostic().with_context(|| format!("Failed to access '{}'", path.display()))? {
+        return Err(miette!("Config file '{}' already exists; remove it first to reset to defaults", path.display()));
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).into_diagnostic()
+            .with_context(|| format!("Failed to create '{}'", parent.display()))?;
+    }
+
+    let toml = toml::to_string_pretty(&config).into_diagnostic().context("Failed to serialize default config")?;
+    std::fs::write(&path, toml).into_diagnostic().with_context(|| format!("Failed to write '{}'", path.display()))?;
+    Ok(())
+}
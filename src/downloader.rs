@@ -0,0 +1,220 @@
+use crate::error::NvimError;
+use reqwest::{Client, StatusCode, Url};
+use miette::{Context, IntoDiagnostic, Result};
+use tokio::{fs, io::AsyncWriteExt};
+use futures_util::{StreamExt, stream::BoxStream};
+use std::{io, path::PathBuf, os::unix::prelude::PermissionsExt};
+
+/// A single asset for the [`Downloader`] to fetch.
+pub struct FileToDownload {
+    pub url: Url,
+    pub dest: PathBuf,
+    pub content_type: String,
+    pub executable: bool,
+    /// Whether a leftover partial at `dest` should be resumed with a `Range`
+    /// request. Only worthwhile for large assets; small files (e.g. the
+    /// checksum) set this to `false` so a complete leftover is refetched
+    /// cleanly instead of provoking an unsatisfiable range.
+    pub resumable: bool
+}
+
+/// Lifecycle events emitted per file so callers can render progress however
+/// they like instead of the `Downloader` hard-coding an `indicatif` bar.
+pub enum Progress {
+    /// The transfer began; `total` is the full asset size when the server
+    /// advertises one (already-downloaded bytes included on a resume).
+    Started { total: Option<u64> },
+    /// Cumulative bytes written to disk so far.
+    Bytes(u64),
+    Finished,
+    Failed
+}
+
+/// A started HTTP transfer, abstracted over the concrete client so the
+/// streaming loop can be driven by a mock in tests.
+pub struct HttpResponse {
+    status: StatusCode,
+    content_length: Option<u64>,
+    body: BoxStream<'static, io::Result<Vec<u8>>>
+}
+
+/// The seam the [`Downloader`] drives its transfers through. Implemented for
+/// `&reqwest::Client` in production and by a mock in the unit tests.
+pub trait HttpClient {
+    /// Issue a GET, optionally resuming from `range` bytes with a `Range`
+    /// header, and yield the response body as a byte stream.
+    async fn get(&self, url: &Url, range: Option<u64>) -> io::Result<HttpResponse>;
+}
+
+impl HttpClient for &Client {
+    async fn get(&self, url: &Url, range: Option<u64>) -> io::Result<HttpResponse> {
+        let mut req = (*self).get(url.clone()).header("User-Agent", "request");
+        if let Some(start) = range {
+            req = req.header("Range", format!("bytes={start}-"));
+        }
+        let res = req.send().await.map_err(io::Error::other)?;
+        Ok(HttpResponse {
+            status: res.status(),
+            content_length: res.content_length(),
+            body: res.bytes_stream().map(|r| r.map(|b| b.to_vec()).map_err(io::Error::other)).boxed()
+        })
+    }
+}
+
+/// Drives a batch of [`FileToDownload`]s through one streaming code path,
+/// with resume support and a pluggable progress callback.
+pub struct Downloader<C> {
+    client: C
+}
+
+impl<C: HttpClient> Downloader<C> {
+    pub fn new(client: C) -> Self {
+        Self { client }
+    }
+
+    /// Fetch every file in order, reporting progress through `on_progress`.
+    pub async fn fetch(&self, files: &[FileToDownload], mut on_progress: impl FnMut(Progress)) -> Result<()> {
+        for file in files {
+            self.fetch_one(file, &mut on_progress).await?;
+        }
+        Ok(())
+    }
+
+    async fn fetch_one(&self, file: &FileToDownload, on_progress: &mut impl FnMut(Progress)) -> Result<()> {
+        let ctx = || format!("Failed to download '{}' ({})", file.dest.display(), file.content_type);
+
+        // Resume a previous transfer if a resumable partial is still staged at
+        // `dest`. Non-resumable assets always refetch, so a complete leftover
+        // never turns into an unsatisfiable `Range` request.
+        let partial = if file.resumable {
+            fs::metadata(&file.dest).await.map(|m| m.len()).unwrap_or(0)
+        } else {
+            0
+        };
+        let res = match self.client.get(&file.url, (partial > 0).then_some(partial)).await {
+            Ok(res) => res,
+            Err(e) => { on_progress(Progress::Failed); return Err(e).into_diagnostic().with_context(ctx); }
+        };
+        let remaining = res.content_length;
+
+        // A `206 Partial Content` means the range was honoured, so the body is
+        // only the tail and the full size is `partial + remaining`. A plain
+        // `200 OK` means the server ignored the range and is resending the
+        // whole asset, so start from scratch. Anything else (e.g. a `416` from
+        // a range past the end of an already-complete file) is not a body we
+        // can treat as file contents, so bail instead of truncating `dest`.
+        let (total, mut downloaded, mut handle) = if res.status == StatusCode::PARTIAL_CONTENT {
+            let handle = fs::OpenOptions::new().append(true).open(&file.dest).await.into_diagnostic().with_context(ctx)?;
+            (remaining.map(|r| partial + r), partial, handle)
+        } else if res.status == StatusCode::OK {
+            let handle = fs::OpenOptions::new().create(true).write(true).truncate(true)
+                .open(&file.dest).await.into_diagnostic().with_context(ctx)?;
+            (remaining, 0, handle)
+        } else {
+            on_progress(Progress::Failed);
+            return Err(miette::miette!("Unexpected HTTP status {} while downloading '{}'", res.status, file.dest.display()))
+                .with_context(ctx);
+        };
+
+        on_progress(Progress::Started { total });
+
+        let mut stream = res.body;
+        while let Some(item) = stream.next().await {
+            let chunk = match item {
+                Ok(chunk) => chunk,
+                Err(e) => { on_progress(Progress::Failed); return Err(e).into_diagnostic().with_context(ctx); }
+            };
+            handle.write_all(&chunk).await.into_diagnostic().with_context(ctx)?;
+            downloaded += chunk.len() as u64;
+            on_progress(Progress::Bytes(total.map_or(downloaded, |t| downloaded.min(t))));
+        }
+
+        if file.executable {
+            let mut perms = handle.metadata().await.into_diagnostic().with_context(ctx)?.permissions();
+            perms.set_mode(0o755);
+            handle.set_permissions(perms).await
+                .map_err(|source| NvimError::Permissions { path: file.dest.display().to_string(), source })?;
+        }
+
+        on_progress(Progress::Finished);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use std::{collections::VecDeque, sync::Mutex};
+
+    /// Canned-response client that records the `Range` offset of each request.
+    struct MockClient {
+        responses: Mutex<VecDeque<HttpResponse>>,
+        ranges: Mutex<Vec<Option<u64>>>
+    }
+
+    impl MockClient {
+        fn new(responses: impl IntoIterator<Item = HttpResponse>) -> Self {
+            Self { responses: Mutex::new(responses.into_iter().collect()), ranges: Mutex::new(Vec::new()) }
+        }
+    }
+
+    impl HttpClient for MockClient {
+        async fn get(&self, _url: &Url, range: Option<u64>) -> io::Result<HttpResponse> {
+            self.ranges.lock().unwrap().push(range);
+            Ok(self.responses.lock().unwrap().pop_front().expect("unexpected extra request"))
+        }
+    }
+
+    fn response(status: u16, content_length: Option<u64>, body: &[u8]) -> HttpResponse {
+        let body = body.to_vec();
+        HttpResponse {
+            status: StatusCode::from_u16(status).unwrap(),
+            content_length,
+            body: stream::once(async move { Ok(body) }).boxed()
+        }
+    }
+
+    fn file(dest: PathBuf, resumable: bool) -> FileToDownload {
+        FileToDownload {
+            url: "https://example.invalid/asset".parse().unwrap(),
+            dest, content_type: "application/octet-stream".to_owned(), executable: false, resumable
+        }
+    }
+
+    #[tokio::test]
+    async fn fresh_download_writes_full_body() {
+        let dest = std::env::temp_dir().join("nvim_upgrade_fresh.bin");
+        let _ = std::fs::remove_file(&dest);
+
+        let client = MockClient::new([response(200, Some(5), b"hello")]);
+        let mut totals = Vec::new();
+        Downloader::new(client).fetch(&[file(dest.clone(), true)], |e| {
+            if let Progress::Started { total } = e { totals.push(total); }
+        }).await.unwrap();
+
+        assert_eq!(std::fs::read(&dest).unwrap(), b"hello");
+        assert_eq!(totals, vec![Some(5)]);
+        std::fs::remove_file(&dest).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resume_appends_tail_and_sends_range() {
+        let dest = std::env::temp_dir().join("nvim_upgrade_resume.bin");
+        std::fs::write(&dest, b"hel").unwrap();
+
+        // Server honours the range: body is only the 2-byte tail.
+        let client = MockClient::new([response(206, Some(2), b"lo")]);
+        let downloader = Downloader::new(client);
+        let mut totals = Vec::new();
+        downloader.fetch(&[file(dest.clone(), true)], |e| {
+            if let Progress::Started { total } = e { totals.push(total); }
+        }).await.unwrap();
+
+        assert_eq!(std::fs::read(&dest).unwrap(), b"hello");
+        // Range offset is the already-downloaded length; total covers the whole asset.
+        assert_eq!(*downloader.client.ranges.lock().unwrap(), vec![Some(3)]);
+        assert_eq!(totals, vec![Some(5)]);
+        std::fs::remove_file(&dest).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+use miette::Diagnostic;
+use thiserror::Error;
+
+/// Typed failures that warrant a diagnostic code and a concrete fix hint.
+///
+/// Everything else stays an ad-hoc [`miette`] report with context; these are
+/// the cases where we can point the user at a specific remedy.
+#[derive(Debug, Error, Diagnostic)]
+pub enum NvimError {
+    /// Changing the mode bits on the freshly downloaded binary was denied.
+    #[error("Failed to set executable permissions on '{path}'")]
+    #[diagnostic(
+        code(nvim_upgrade::permissions),
+        help("Re-run with sufficient privileges (e.g. `sudo`), or point `install_dir` at a directory you own.")
+    )]
+    Permissions {
+        path: String,
+        #[source] source: std::io::Error
+    },
+
+    /// Polling the GitHub releases API failed, either sending the request or
+    /// decoding its JSON response.
+    #[error("Failed to poll the Neovim releases API")]
+    #[diagnostic(
+        code(nvim_upgrade::api_poll),
+        help("Check your network connection and whether you've hit GitHub's API rate limit.")
+    )]
+    ApiPoll {
+        #[source] source: reqwest::Error
+    },
+
+    /// The identity line in the release body did not contain a usable semver.
+    #[error("Could not parse a version from release body line: {line:?}")]
+    #[diagnostic(
+        code(nvim_upgrade::version_parse),
+        help("Expected the line to look like `NVIM vX.Y.Z`; the release body may have changed format.")
+    )]
+    VersionParse {
+        line: String,
+        #[source] source: semver::Error
+    },
+
+    /// The locally cached `current_version` file held a payload that doesn't
+    /// parse as semver, e.g. hand-edited or truncated by a crash mid-write.
+    #[error("Could not parse a version from the local version file: {line:?}")]
+    #[diagnostic(
+        code(nvim_upgrade::local_version_parse),
+        help("The stored version is corrupt; delete the version file (or run `rollback`) to force a fresh install.")
+    )]
+    LocalVersionParse {
+        line: String,
+        #[source] source: semver::Error
+    }
+}
@@ -1,39 +1,117 @@
+mod config;
+mod downloader;
+mod error;
+
 use MyExit::*;
+use config::Paths;
+use error::NvimError;
+use downloader::{Downloader, FileToDownload, Progress};
 use serde::Deserialize;
 use reqwest::{Client, Url};
 use semver::Version;
-use bunt::{println as bprintln, eprintln as ebprintln};
-use tokio::{fs, runtime::{Builder, Runtime}, io::AsyncWriteExt};
-use join::{try_async_spawn, try_spawn, try_join, join};
+use clap::{Parser, Subcommand, ValueEnum};
+use bunt::println as bprintln;
+use tokio::{fs, runtime::{Builder, Runtime}};
+use join::{try_join_async, try_spawn, try_join, join};
 use partial_application::partial;
-use anyhow::{Context, Result, Error as AnyError, anyhow};
+use miette::{Context, IntoDiagnostic, Result, Report, miette};
 use indicatif::{ProgressBar, ProgressStyle};
-use futures_util::StreamExt;
+use sha2::{Sha256, Digest};
 use once_cell::sync::OnceCell;
 use std::{
-    cmp::{Ordering::*, min}, path::Path,
-    process::{ExitCode, Termination},
-    os::unix::prelude::PermissionsExt
+    cmp::Ordering::*, fmt,
+    process::{ExitCode, Termination}
 };
 
-const VERSION: &str = "/opt/neovim/current_version";
-const APP_PATH: &str = "/opt/neovim/nvim.appimage";
-const NVIM_API: &str = "https://api.github.com/repos/neovim/neovim/releases/latest";
 static CLIENT: OnceCell<Client> = OnceCell::new();
 
 fn get_client() -> Result<&'static Client> {
-    CLIENT.get().ok_or_else(|| anyhow!("Failed to access CLIENT."))
+    CLIENT.get().ok_or_else(|| miette!("Failed to access CLIENT."))
+}
+
+#[derive(Parser)]
+#[command(author, version, about = "Keep your Neovim AppImage up to date")]
+struct Args {
+    /// Release channel to track.
+    #[arg(long, value_enum, default_value_t = Channel::Stable)]
+    channel: Channel,
+
+    #[command(subcommand)]
+    command: Option<Command>
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the install directory and write a default config file.
+    Init,
+    /// Restore the previous binary and version after a bad upgrade.
+    Rollback
+}
+
+/// The Neovim release channel the tool follows.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Channel {
+    Stable,
+    Nightly
+}
+
+impl Channel {
+    /// The GitHub releases endpoint this channel polls, under `base`.
+    fn api_url(self, base: &str) -> String {
+        let base = base.trim_end_matches('/');
+        match self {
+            Self::Stable => format!("{base}/latest"),
+            Self::Nightly => format!("{base}/tags/nightly")
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self { Self::Stable => "stable", Self::Nightly => "nightly" })
+    }
+}
+
+/// An installed or available Neovim build.
+///
+/// Stable builds carry a real semver; nightly builds have none, so we fall back
+/// to the commit/date identity line from the release body to decide freshness.
+#[derive(Clone, PartialEq, Eq)]
+enum Release {
+    Stable(Version),
+    Nightly(String)
+}
+
+impl Release {
+    /// The bare identity stored in the version file (no channel prefix).
+    fn payload(&self) -> String {
+        match self {
+            Self::Stable(v) => v.to_string(),
+            Self::Nightly(id) => id.clone()
+        }
+    }
+}
+
+impl fmt::Display for Release {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stable(v) => write!(f, "v{v}"),
+            Self::Nightly(id) => write!(f, "nightly ({id})")
+        }
+    }
 }
 
 enum MyExit {
     Success(()),
-    Fail(AnyError)
+    Fail(Report)
 }
 
 impl Termination for MyExit {
     fn report(self) -> ExitCode {
         if let Self::Fail(e) = self {
-            ebprintln!("{[red+bold]:?}", e);
+            // `Report`'s `Debug` renders the full diagnostic — code, cause chain
+            // and help text — through `miette`'s graphical handler.
+            eprintln!("{e:?}");
             ExitCode::FAILURE
         } else { ExitCode::SUCCESS }
     }
@@ -55,89 +133,190 @@ macro_rules! gen_ctx { ($path:expr) => {
     format!("Failed to access '{}'", $path)
 }}
 
-async fn get_current(read_file: bool) -> Result<Version> {
-    if read_file {
-        fs::read_to_string(VERSION).await.with_context(|| gen_ctx!(VERSION))?.as_str().parse()
+async fn get_current(read_file: bool, channel: Channel, paths: &Paths) -> Result<Release> {
+    let raw = if read_file {
+        fs::read_to_string(&paths.version).await.into_diagnostic().with_context(|| gen_ctx!(paths.version.display()))?
     } else {
-        "0.0.0".parse()
-    }.context("Failed to parse current nvim version")
+        String::new()
+    };
+
+    // `current_version` is `<channel>:<payload>`; a missing file or a different
+    // channel means nothing comparable is installed, so force a fresh download.
+    let (stored_channel, payload) = raw.trim().split_once(':').unwrap_or(("", ""));
+    if stored_channel != channel.to_string() {
+        return Ok(match channel {
+            Channel::Stable => Release::Stable(Version::new(0, 0, 0)),
+            Channel::Nightly => Release::Nightly(String::new())
+        });
+    }
+
+    Ok(match channel {
+        Channel::Stable => Release::Stable(
+            payload.parse().map_err(|source| NvimError::LocalVersionParse { line: payload.to_owned(), source })?
+        ),
+        Channel::Nightly => Release::Nightly(payload.to_owned())
+    })
 }
 
-async fn get_latest() -> Result<(Version, Url)> {
+async fn get_latest(channel: Channel, paths: &Paths) -> Result<(Release, Url, Url)> {
     bprintln!("{$green}Polling {$bold}Neovim{/$} GitHub releases API...{/$}");
     let res: NvimResponse = get_client()?
-        .get(NVIM_API).header("User-Agent", "request")
-        .send().await.context("JSON Request Failed")?
-        .json().await.context("JSON Conversion Failed")?;
+        .get(channel.api_url(&paths.api_base)).header("User-Agent", "request")
+        .send().await.map_err(|source| NvimError::ApiPoll { source })?
+        .json().await.map_err(|source| NvimError::ApiPoll { source })?;
+
+    // Both channels embed a `NVIM v...` line in the release body; stable reads
+    // `v0.10.0` off it, while nightly's carries the `-dev-<n>+g<hash>` suffix
+    // that uniquely identifies the build. Match the marker rather than a fixed
+    // line index, since the nightly body does not share the stable layout.
+    let ident_line = res.body
+        .lines().map(str::trim).find(|l| l.starts_with("NVIM "))
+        .ok_or_else(|| miette!("Could not find the 'NVIM ...' line in release body"))?;
 
-    let version = res.body
-        .lines().nth(1).ok_or_else(|| anyhow!("Could not get second line of 'body'"))?
-        .split(' ').nth(1).ok_or_else(|| anyhow!("Could not get second segment of second line of 'body'"))?
-        .strip_prefix('v').ok_or_else(|| anyhow!("Could not strip 'v' from segment"))?
-        .parse().context("Failed to parse version from 'body'")?;
+    let release = match channel {
+        Channel::Stable => Release::Stable(ident_line
+            .split(' ').nth(1).ok_or_else(|| miette!("Could not get version segment of 'NVIM' line"))?
+            .strip_prefix('v').ok_or_else(|| miette!("Could not strip 'v' from segment"))?
+            .parse().map_err(|source| NvimError::VersionParse { line: ident_line.to_owned(), source })?),
+        // Nightly has no semver, so the commit-bearing marker line is the identity.
+        Channel::Nightly => Release::Nightly(ident_line.to_owned())
+    };
 
     let down_url = res.assets
-        .into_iter().find(|a| a.content_type == "application/vnd.appimage")
-        .ok_or_else(|| anyhow!("Could not find correct asset"))?
-        .browser_download_url.as_str().parse()
+        .iter().find(|a| a.content_type == "application/vnd.appimage")
+        .ok_or_else(|| miette!("Could not find correct asset"))?
+        .browser_download_url.as_str().parse().into_diagnostic()
         .context("Failed to parse Url from JSON")?;
 
-    Ok((version, down_url))
-}
+    let sum_url = res.assets
+        .iter().find(|a| a.browser_download_url.ends_with(&paths.sum_name))
+        .ok_or_else(|| miette!("Could not find checksum asset"))?
+        .browser_download_url.as_str().parse().into_diagnostic()
+        .context("Failed to parse checksum Url from JSON")?;
 
-async fn do_upgrade(down_url: Url) -> Result<()> {
-    let res = get_client()?.get(down_url).send().await.context("Download GET request failed")?;
-    let total_size = res.content_length().ok_or_else(|| anyhow!("Failed to get size of response body."))?;
+    Ok((release, down_url, sum_url))
+}
 
-    let pb = ProgressBar::new(total_size).with_style(ProgressStyle::default_bar()
+/// Render [`Downloader`] progress events as an `indicatif` bar.
+fn progress_bar() -> Result<impl FnMut(Progress)> {
+    let style = ProgressStyle::default_bar()
         .template("{spinner:.green} [{elapsed_precise}] [{bar:50.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
-        .context("Error while downloading `nvim.appimage`")?
-        .progress_chars("#>-"));
-
-    let mut file = fs::OpenOptions::new().create(true).write(true).open(APP_PATH)
-        .await.with_context(|| gen_ctx!(APP_PATH))?;
-    let mut downloaded = 0;
-    let mut stream = res.bytes_stream();
-
-    while let Some(item) = stream.next().await {
-        let chunk = item.context("Error while downloading 'nvim.appimage'")?;
-        file.write_all(&chunk).await.with_context(|| format!("Error while writing to '{APP_PATH}'"))?;
-        let new = min(downloaded + (chunk.len() as u64), total_size);
-        downloaded = new;
-        pb.set_position(new);
+        .into_diagnostic().context("Error while downloading `nvim.appimage`")?
+        .progress_chars("#>-");
+
+    let mut bar: Option<ProgressBar> = None;
+    Ok(move |event| match event {
+        Progress::Started { total } => {
+            let pb = total.map_or_else(ProgressBar::new_spinner, ProgressBar::new);
+            pb.set_style(style.clone());
+            bar = Some(pb);
+        },
+        Progress::Bytes(bytes) => if let Some(pb) = &bar { pb.set_position(bytes); },
+        Progress::Finished => if let Some(pb) = bar.take() { pb.finish(); },
+        Progress::Failed => if let Some(pb) = bar.take() { pb.abandon(); }
+    })
+}
+
+async fn do_upgrade(down_url: Url, sum_url: Url, paths: &Paths) -> Result<()> {
+    // Fetch the checksum and the AppImage through the one `Downloader` path.
+    let files = [
+        FileToDownload {
+            url: sum_url, dest: paths.sum_path.clone(),
+            content_type: "text/plain".to_owned(), executable: false, resumable: false
+        },
+        FileToDownload {
+            url: down_url, dest: paths.app_new.clone(),
+            content_type: "application/vnd.appimage".to_owned(), executable: true, resumable: true
+        }
+    ];
+    Downloader::new(get_client()?).fetch(&files, progress_bar()?).await?;
+
+    // The checksum file is `<hex>  nvim.appimage`; we only need the digest.
+    let expected = fs::read_to_string(&paths.sum_path).await.into_diagnostic().with_context(|| gen_ctx!(paths.sum_path.display()))?
+        .split_whitespace().next().ok_or_else(|| miette!("Checksum file was empty"))?
+        .to_lowercase();
+
+    // Verify integrity before the download is swapped in; a mismatch means a
+    // truncated or tampered asset, so discard the partial file. We hash the
+    // finished file off disk rather than streaming each chunk through the
+    // hasher in `Downloader`: a resumed transfer (see the `Range` path) writes
+    // only the tail, so the bytes already on disk would never reach an
+    // in-loop hasher and the digest would be wrong.
+    let digest = format!("{:x}", Sha256::digest(
+        fs::read(&paths.app_new).await.into_diagnostic().with_context(|| gen_ctx!(paths.app_new.display()))?
+    ));
+    if digest != expected {
+        fs::remove_file(&paths.app_new).await.into_diagnostic()
+            .with_context(|| format!("Failed to remove corrupt '{}'", paths.app_new.display()))?;
+        return Err(miette!("SHA-256 mismatch: expected {expected}, got {digest}"));
+    }
+
+    // Back up the live binary and the version string it corresponds to,
+    // together, now that the new download is verified and we're committed to
+    // the swap; `rollback` restores both so it never mismatches a binary from
+    // one attempt with the version string from another.
+    if paths.app_path.try_exists().into_diagnostic().with_context(|| gen_ctx!(paths.app_path.display()))? {
+        fs::rename(&paths.app_path, &paths.app_bak).await.into_diagnostic()
+            .with_context(|| format!("Failed to back up '{}' to '{}'", paths.app_path.display(), paths.app_bak.display()))?;
+        if paths.version.try_exists().into_diagnostic().with_context(|| gen_ctx!(paths.version.display()))? {
+            fs::copy(&paths.version, &paths.version_bak).await.into_diagnostic()
+                .with_context(|| format!("Failed to back up '{}' to '{}'", paths.version.display(), paths.version_bak.display()))?;
+        }
+    }
+    fs::rename(&paths.app_new, &paths.app_path).await.into_diagnostic()
+        .with_context(|| format!("Failed to move '{}' into place", paths.app_new.display()))
+}
+
+async fn rollback(paths: &Paths) -> Result<()> {
+    if !paths.app_bak.try_exists().into_diagnostic().with_context(|| gen_ctx!(paths.app_bak.display()))? {
+        return Err(miette!("No backup to roll back to"));
     }
 
-    pb.finish();
+    fs::rename(&paths.app_bak, &paths.app_path).await.into_diagnostic()
+        .with_context(|| format!("Failed to restore '{}' from '{}'", paths.app_path.display(), paths.app_bak.display()))?;
+    if paths.version_bak.try_exists().into_diagnostic().with_context(|| gen_ctx!(paths.version_bak.display()))? {
+        fs::rename(&paths.version_bak, &paths.version).await.into_diagnostic()
+            .with_context(|| format!("Failed to restore '{}' from '{}'", paths.version.display(), paths.version_bak.display()))?;
+    }
 
-    file.set_permissions({
-        let mut perms = file
-            .metadata().await.with_context(|| format!("Could not get metadata from '{APP_PATH}'"))?
-            .permissions();
-        perms.set_mode(0o755);
-        perms
-    }).await.with_context(|| format!("Failed to set file permissions for '{APP_PATH}'"))
+    let _ = bprintln!("{$green}Rolled back to the previous {$bold}Neovim{/$} build.{/$}");
+    Ok(())
 }
 
-async fn run(read_file: bool) -> Result<()> {
-    let (current, (latest, down_url)) = try_async_spawn!(read_file -> get_current, get_latest()).await?;
+async fn run(read_file: bool, channel: Channel, paths: &Paths) -> Result<()> {
+    // Await both concurrently without `tokio::spawn`; the futures borrow the
+    // local `paths`, so they must not be detached onto `'static` tasks.
+    let (current, (latest, down_url, sum_url)) = try_join_async!(
+        get_current(read_file, channel, paths), get_latest(channel, paths)
+    ).await?;
 
-    match latest.cmp(&current) {
-        Equal => Ok(bprintln!("{$green}{$bold}Neovim{/$} is up to date!{/$} {$dimmed}(v{}){/$}", current)),
-        Greater => {
-            bprintln!("{$green}Downloading latest version...{/$} {$dimmed}(v{}){/$}", latest);
-            do_upgrade(down_url).await?;
-            fs::write(VERSION, latest.to_string()).await
-                .with_context(|| format!("Failed to write new version to '{VERSION}'"))?;
-            Ok(bprintln!("{$green}Done!{/$}"))
+    // Stable builds are ordered; nightly only supports "same or not", in which
+    // case any difference from the stored identity means a newer build exists.
+    let up_to_date = match (&current, &latest) {
+        (Release::Stable(c), Release::Stable(l)) => match l.cmp(c) {
+            Less => return Err(miette!("How did you get a newer version than the latest?")),
+            ord => ord == Equal
         },
-        _ => Err(anyhow!("How did you get a newer version than the latest?"))
+        _ => current == latest
+    };
+
+    if up_to_date {
+        let _ = bprintln!("{$green}{$bold}Neovim{/$} is up to date!{/$} {$dimmed}({}){/$}", current);
+        Ok(())
+    } else {
+        bprintln!("{$green}Downloading latest version...{/$} {$dimmed}({}){/$}", latest);
+        do_upgrade(down_url, sum_url, paths).await?;
+        fs::write(&paths.version, format!("{channel}:{}", latest.payload())).await.into_diagnostic()
+            .with_context(|| format!("Failed to write new version to '{}'", paths.version.display()))?;
+        let _ = bprintln!("{$green}Done!{/$}");
+        Ok(())
     }
 }
 
-fn check_files() -> Result<bool> {
-    let [res1, res2] = [APP_PATH, VERSION].map(|p| Path::new(p)
-        .try_exists()
-        .with_context(|| format!("Failed to access '{p}'")));
+fn check_files(paths: &Paths) -> Result<bool> {
+    let [res1, res2] = [&paths.app_path, &paths.version].map(|p| p
+        .try_exists().into_diagnostic()
+        .with_context(|| format!("Failed to access '{}'", p.display())));
 
     try_spawn!(res1, res2).map(|t| if t.0 && t.1 { true } else {
         bprintln!("{$yellow+bold}No (valid) Neovim Installation Found.{/$}");
@@ -145,25 +324,36 @@ fn check_files() -> Result<bool> {
     })
 }
 
-fn async_handle(rt: Runtime) -> Result<()> {
+fn async_handle(rt: Runtime, channel: Channel, command: Option<Command>) -> Result<()> {
+    let paths = config::load()?.paths();
+
     try_join! {
         Client::builder()
         >. build()
+        >. into_diagnostic()
         >. context("Failed to build client")
         => >>> -> partial!(OnceCell::set => &CLIENT, _)
-        !> |_| anyhow!("Failed to initialize CLIENT")
+        !> |_| miette!("Failed to initialize CLIENT")
     }?;
-    
-    rt.block_on(run(check_files()?))
+
+    rt.block_on(async {
+        match command {
+            Some(Command::Init) => config::init(),
+            Some(Command::Rollback) => rollback(&paths).await,
+            None => run(check_files(&paths)?, channel, &paths).await
+        }
+    })
 }
 
 fn main() -> MyExit {
+    let args = Args::parse();
     join! {
         Builder::new_multi_thread()
         >. enable_all()
         >. build()
+        >. into_diagnostic()
         >. context("Failed to build runtime")
-        => async_handle
+        => partial!(async_handle => _, args.channel, args.command)
         >. map_or_else(Fail, Success)
     }
 }